@@ -27,7 +27,7 @@ fn main()->std::io::Result<()> {
             rv_r.r0.x=((i_col as f64)/(n_cols as f64)-0.5)*16.0/4.0;
             rv_r.r0.y=((i_row as f64)/(n_rows as f64)-0.5)* 9.0/4.0;
             match union.intersect(&rv_r) {
-                Some(t) => {
+                Some((t,_hit,_normal)) => {
                     *pix=(t * 128.0) as u8;
                 },
                 None    => ()
@@ -1,16 +1,82 @@
-use crate::vector::{Direction, HMatrix, Matrix3x3, Ray, Vector};
-use crate::transform::{Transform, TransformList, Translate};
+use crate::vector::{Direction, HMatrix, Matrix3x3, Position, Quaternion, Ray, Vector};
+use crate::transform::{RotateAxis, RotateQuat, Similarity, Transform, TransformList, Translate, UniformScale};
 
 pub trait Render {
-    fn intersect_local(&self, rv_b:&Ray) ->Option<f64>;
+    /// Sorted-by-entry list of body-frame ray-parameter spans `(t_enter,t_exit)`
+    /// during which the ray is inside the solid. A convex primitive like
+    /// `Sphere` returns at most one span; a half-space like `Plane` returns
+    /// one span open at `+inf` or `-inf`; CSG nodes combine their children's
+    /// spans.
+    fn intersect_spans_local(&self, rv_b:&Ray) ->Vec<(f64,f64)>;
+    /// Smallest span endpoint that is >=0, which recovers the old
+    /// closest-hit behavior from the span list. A span open at `+inf`
+    /// (e.g. the ray origin inside a `Plane` half-space) has no finite
+    /// boundary to report, so an `inf` endpoint is excluded along with the
+    /// negative ones.
+    fn intersect_local(&self, rv_b:&Ray) ->Option<f64> {
+        self.intersect_spans_local(rv_b).into_iter()
+            .flat_map(|(t0,t1)| [t0,t1])
+            .filter(|t| *t>=0.0 && t.is_finite())
+            .fold(None,|best,t| Some(match best {
+                Some(best) => best.min(t),
+                None       => t,
+            }))
+    }
+    /// Surface normal in the body frame at ray parameter `t` along the
+    /// body-frame ray `rv_b`, where `t` is a value `intersect_spans_local`
+    /// returned for that same ray. A primitive computes the hit point and
+    /// its normal directly; a composite instead has to work out which
+    /// child contributed that span endpoint and recurse into it.
+    fn normal_local(&self, rv_b:&Ray, t:f64)->Direction;
+    /// Body-frame bounding sphere (center, radius), used to cheaply reject a
+    /// ray before testing the full surface. `None` means no bound is
+    /// reported: an infinite `Plane` is unbounded, and `Intersection` and
+    /// `Difference` do not compute a bound from their children.
+    fn bound_local(&self)->Option<(Position,f64)> {
+        None
+    }
     fn M_rb(&self)->&HMatrix;
     fn M_br(&self)->&HMatrix;
     fn translate(&mut self,x:f64,y:f64,z:f64) {
         let T=Translate::make(x,y,z);
         self.get_transforms().push(Box::new(T));
     }
-    fn intersect(&self, rv_r:&Ray)->Option<f64> {
-        self.intersect_local(&self.M_br().mul_ray(rv_r))
+    fn scale(&mut self,s:f64) {
+        self.get_transforms().push(Box::new(UniformScale::make(s)));
+    }
+    fn rotate_axis(&mut self,axis:Direction,angle:f64) {
+        self.get_transforms().push(Box::new(RotateAxis::make(axis,angle)));
+    }
+    fn rotate_quat(&mut self,q:Quaternion) {
+        self.get_transforms().push(Box::new(RotateQuat::make(q)));
+    }
+    /// A uniform scale, a rotation, and a translation folded into a single
+    /// transform node, rather than three separate `TransformList` entries.
+    fn similarity(&mut self,scale:f64,rotation:Quaternion,translation:Direction) {
+        self.get_transforms().push(Box::new(Similarity::make(scale,rotation,translation)));
+    }
+    /// `similarity`, but the rotation is given as an axis and angle rather
+    /// than a `Quaternion` directly.
+    fn similarity_axis_angle(&mut self,scale:f64,axis:&Direction,angle:f64,translation:Direction) {
+        self.get_transforms().push(Box::new(Similarity::from_axis_angle(scale,axis,angle,translation)));
+    }
+    /// Intersect a ray given in the reference frame, returning the ray
+    /// parameter, the hit point, and the surface normal there — all three
+    /// in the reference frame — so shading code downstream has everything
+    /// it needs without a second, position-only lookup that a composite
+    /// couldn't resolve on its own.
+    ///
+    /// The body-frame normal is mapped back to the reference frame by the
+    /// inverse-transpose of the upper 3x3 block of `M_rb`, not by `M_rb`
+    /// itself, since that's what keeps it perpendicular to a scaled/sheared
+    /// surface.
+    fn intersect(&self, rv_r:&Ray)->Option<(f64,Position,Direction)> {
+        let rv_b=self.M_br().mul_ray(rv_r);
+        self.intersect_local(&rv_b).map(|t| {
+            let p=Position{x:rv_r.r0.x+rv_r.v.x*t, y:rv_r.r0.y+rv_r.v.y*t, z:rv_r.r0.z+rv_r.v.z*t};
+            let n=self.M_rb().mul_normal(&self.normal_local(&rv_b,t));
+            (t,p,n)
+        })
     }
     fn get_transforms(&mut self)->&mut TransformList;
     fn set_M_rb(&mut self,M_rb:HMatrix);
@@ -48,35 +114,106 @@ impl Render for Sphere {
     ///  * a=vx^2+vy^2+vz^2=v.v
     ///  * b=2*(x0*vx+y0*vy+z0*vz)=2*(r0.v)
     ///  * c=(x0^2+y0^2+z0^2-1)=r0.r0-1
-    fn intersect_local(&self, rv: &Ray) -> Option<f64> {
+    ///
+    /// The ray is inside the sphere between the smaller and larger root,
+    /// `tm` and `tp`; there's no span at all when the discriminant is negative.
+    fn intersect_spans_local(&self, rv: &Ray) -> Vec<(f64,f64)> {
         let a=rv.v.dot(&rv.v);
         let b=2.0*rv.r0.dot(&rv.v);
         let c=rv.r0.dot(&rv.r0)-1.0;
         let d=b*b-4.0*a*c;
         if d<0.0 {
-            None
+            vec![]
         } else {
             let tp=(-b+d.sqrt())/(2.0*a);
             let tm=(-b-d.sqrt())/(2.0*a);
-            if tp<0.0 && tm<0.0 {
-                // Both intersections behind camera
-                None
-            } else if tp<0.0 {
-                // tp is behind camera, return tm
-                Some(tm)
-            } else if tm<0.0 {
-                // tm is behind camera, return tp
-                Some(tp)
-            } else if tp<tm {
-                // both are in front, tp is closer
-                Some(tp)
+            vec![(tm,tp)]
+        }
+    }
+
+    /// The unit sphere's local normal at a surface point is just the point
+    /// itself, taken as a direction.
+    fn normal_local(&self, rv_b: &Ray, t:f64) -> Direction {
+        Direction{x:rv_b.r0.x+rv_b.v.x*t, y:rv_b.r0.y+rv_b.v.y*t, z:rv_b.r0.z+rv_b.v.z*t}
+    }
+
+    fn bound_local(&self) -> Option<(Position,f64)> {
+        Some((Position{x:0.0,y:0.0,z:0.0},1.0))
+    }
+
+    fn M_rb(&self) -> &HMatrix {
+        &self._m_rb
+    }
+
+    fn M_br(&self) -> &HMatrix {
+        &self._m_br
+    }
+
+    fn set_M_rb(&mut self,M_rb:HMatrix) {
+        self._m_rb=M_rb;
+    }
+
+    fn set_M_br(&mut self,M_br:HMatrix) {
+        self._m_br=M_br;
+    }
+
+    fn get_transforms(&mut self) -> &mut TransformList {
+        &mut self._transforms
+    }
+}
+
+/// An infinite plane / half-space, defined in the body frame by
+/// `n.p + d = 0` for a unit normal `n`, which defaults to +y.
+pub struct Plane {
+    n:Direction,
+    d:f64,
+    _m_rb:HMatrix,
+    _m_br:HMatrix,
+    _transforms:TransformList,
+}
+
+impl Plane {
+    pub fn make()->Plane {
+        Plane{n:Direction{x:0.0,y:1.0,z:0.0}, d:0.0,
+              _m_rb:HMatrix::identity(), _m_br: HMatrix::identity(), _transforms: vec![] }
+    }
+}
+
+impl Render for Plane {
+
+    /// Intersect a ray and the half-space `n.p+d<=0`
+    ///
+    /// The ray is defined by `p=r0+v*t`, so the half-space inequality
+    /// becomes `n.r0+d + t*(n.v) <= 0`. When `n.v` is (nearly) zero the ray
+    /// runs parallel to the plane and is either entirely inside or entirely
+    /// outside, depending on the sign of `n.r0+d`; otherwise it crosses once,
+    /// at `t=-(d+n.r0)/(n.v)`, entering the half-space there if `n.v<0` and
+    /// leaving it there if `n.v>0`.
+    fn intersect_spans_local(&self, rv: &Ray) -> Vec<(f64,f64)> {
+        const EPS:f64=1e-9;
+        let c=self.d+rv.r0.dot(&self.n);
+        let m=rv.v.dot(&self.n);
+        if m.abs()<EPS {
+            if c<=0.0 {
+                vec![(f64::NEG_INFINITY,f64::INFINITY)]
+            } else {
+                vec![]
+            }
+        } else {
+            let t=-c/m;
+            if m<0.0 {
+                vec![(t,f64::INFINITY)]
             } else {
-                // both are in front, tm is closer
-                Some(tm)
+                vec![(f64::NEG_INFINITY,t)]
             }
         }
     }
 
+    /// The plane's normal is the same everywhere on its surface.
+    fn normal_local(&self, _rv_b: &Ray, _t:f64) -> Direction {
+        Direction{x:self.n.x,y:self.n.y,z:self.n.z}
+    }
+
     fn M_rb(&self) -> &HMatrix {
         &self._m_rb
     }
@@ -103,11 +240,14 @@ pub struct Union {
     _transforms:TransformList,
     _m_rb:HMatrix,
     _m_br:HMatrix,
+    /// Each child's bounding sphere, transformed into this `Union`'s body
+    /// frame, in the same order as `itemList`. Filled in by `prepare_render`.
+    _bounds:Vec<Option<(Position,f64)>>,
 }
 
 impl Union {
     pub fn make() -> Union {
-        Union{_m_rb:HMatrix::identity(), _m_br: HMatrix::identity(), _transforms: vec![],itemList:vec![] }
+        Union{_m_rb:HMatrix::identity(), _m_br: HMatrix::identity(), _transforms: vec![],itemList:vec![],_bounds:vec![] }
     }
 }
 
@@ -120,50 +260,229 @@ impl Render for Union {
         for this_render in &mut self.itemList {
             this_render.prepare_render();
         }
+        self._bounds=self.itemList.iter().map(|this_render| {
+            this_render.bound_local().map(|(center,radius)| {
+                let m_rb=this_render.M_rb();
+                (m_rb.mul_pos(&center), radius*frobenius_norm_bound(&m_rb.M))
+            })
+        }).collect();
     }
 
-    fn intersect_local(&self, rv_b: &Ray) -> Option<f64> {
-        /* In many languages, we would keep track of the closest
-           valid parameter, by tracking it. The initial value
-            is either very large or literally infinity, so that
-            any valid parameter is less than it.
-
-            Here instead we use the Option enum. We check
-            the first thing and keep it as a Some(t) or None. We
-            then iterate through the other things. If this one
-            is better (has an intersection while we don't yet,
-            or intersection is closer than current best) we keep
-            the best intersection. When we are done, we return
-            the best intersection, without having to check for
-            thinks like are we still pointing at infinity.
-
-            Note that we are in intersect_local() for the union,
-            but are calling intersect() for the children. Each
-            child performs its own transform and therefore what
-            it considers to be M_rb is actually M_ib where i is
-            the intermediate frame (the Union body frame). This requires
-            one matrix-ray transform for the Union, and one for each
-            child. Maybe later we will concatenate the
-            reference-from-intermediate and intermediate-from-body
-            transformations in prepare_render(). */
-        let mut result=self.itemList[0].intersect(rv_b);
-        for this_render in &self.itemList[1..] {
-            let this_result= this_render.intersect(rv_b);
-            match this_result {
-                Some(_) => {
-                    if result.is_none() {
-                        result=this_result;
-                    } else {
-                        if this_result<result {
-                            result=this_result;
-                        }
-                    }
-                },
-                None    => ()
-            };
+    fn intersect_spans_local(&self, rv_b: &Ray) -> Vec<(f64,f64)> {
+        /* Note that we are in intersect_spans_local() for the union, but
+           are transforming the ray for each child ourselves before asking
+           it for its own spans. Each child's M_rb/M_br is actually M_ib/M_bi
+           where i is the intermediate frame (the Union body frame). This
+           requires one matrix-ray transform per child. Maybe later we will
+           concatenate the reference-from-intermediate and
+           intermediate-from-body transformations in prepare_render(). */
+        let mut spans:Vec<(f64,f64)>=Vec::new();
+        for (this_render,bound) in self.itemList.iter().zip(self._bounds.iter()) {
+            if let Some((center,radius))=bound {
+                if !ray_hits_bound(rv_b,center,*radius) {
+                    continue;
+                }
+            }
+            let rv_i=this_render.M_br().mul_ray(rv_b);
+            spans.extend(this_render.intersect_spans_local(&rv_i));
+        }
+        merge_spans(spans)
+    }
+
+    /// A `Union` has no surface of its own; resolve the normal by finding
+    /// which child contributed span endpoint `t`.
+    fn normal_local(&self, rv_b: &Ray, t:f64) -> Direction {
+        normal_from_children(self.itemList.iter(), rv_b, t)
+    }
+
+    /// The union of the already-transformed child bounds computed in
+    /// `prepare_render`; `None` if any child is unbounded.
+    fn bound_local(&self) -> Option<(Position,f64)> {
+        if self._bounds.is_empty() || self._bounds.iter().any(|b| b.is_none()) {
+            return None;
+        }
+        let n=self._bounds.len() as f64;
+        let (mut cx,mut cy,mut cz)=(0.0,0.0,0.0);
+        for b in &self._bounds {
+            let (c,_)=b.as_ref().unwrap();
+            cx+=c.x; cy+=c.y; cz+=c.z;
+        }
+        let center=Position{x:cx/n,y:cy/n,z:cz/n};
+        let mut radius=0.0f64;
+        for b in &self._bounds {
+            let (c,r)=b.as_ref().unwrap();
+            let dist=((c.x-center.x).powi(2)+(c.y-center.y).powi(2)+(c.z-center.z).powi(2)).sqrt();
+            radius=radius.max(dist+r);
+        }
+        Some((center,radius))
+    }
+
+    fn M_rb(&self) -> &HMatrix {
+        &self._m_rb
+    }
+
+    fn M_br(&self) -> &HMatrix {
+        &self._m_br
+    }
+
+    fn set_M_rb(&mut self,M_rb:HMatrix) {
+        self._m_rb=M_rb;
+    }
+
+    fn set_M_br(&mut self,M_br:HMatrix) {
+        self._m_br=M_br;
+    }
+
+    fn get_transforms(&mut self) -> &mut TransformList {
+        &mut self._transforms
+    }
+
+}
+
+/// Resolve the normal for a composite's span endpoint `t` by finding
+/// whichever child reports `t` as one of its own span endpoints, then
+/// recursing into it. Every endpoint a CSG combinator (`merge_spans`,
+/// `intersect_spans`, `subtract_spans`) can produce is one of its inputs'
+/// original endpoints, so some child is always responsible.
+fn normal_from_children<'a>(children:impl Iterator<Item=&'a Box<dyn Render>>, rv_b:&Ray, t:f64) -> Direction {
+    const EPS:f64=1e-9;
+    for child in children {
+        let rv_i=child.M_br().mul_ray(rv_b);
+        let is_endpoint=child.intersect_spans_local(&rv_i).iter()
+            .any(|&(t0,t1)| (t0-t).abs()<EPS || (t1-t).abs()<EPS);
+        if is_endpoint {
+            let n_i=child.normal_local(&rv_i,t);
+            return child.M_rb().mul_normal(&n_i);
+        }
+    }
+    Direction{x:0.0,y:0.0,z:0.0}
+}
+
+/// Frobenius norm of a 3x3 matrix, used as a conservative scale factor for a
+/// radius carried through that matrix. It's not the matrix's true spectral
+/// norm (its largest singular value) — just an upper bound on it — but
+/// that's all a conservative bounding sphere needs: the largest row norm
+/// isn't even a valid bound, and can understate the true spectral norm for
+/// a sheared or non-uniformly scaled matrix, shrinking a bounding sphere
+/// below the surface it's supposed to contain.
+fn frobenius_norm_bound(m:&Matrix3x3) -> f64 {
+    m.e.iter().flatten().map(|x| x*x).sum::<f64>().sqrt()
+}
+
+/// Cheap ray/bounding-sphere test, reusing the `Sphere` quadratic
+/// discriminant, so `Union` can reject a child without testing its full
+/// surface.
+fn ray_hits_bound(rv:&Ray, center:&Position, radius:f64) -> bool {
+    let oc=Direction{x:rv.r0.x-center.x,y:rv.r0.y-center.y,z:rv.r0.z-center.z};
+    let a=rv.v.dot(&rv.v);
+    let b=2.0*oc.dot(&rv.v);
+    let c=oc.dot(&oc)-radius*radius;
+    b*b-4.0*a*c>=0.0
+}
+
+/// Merge a set of (possibly overlapping, unsorted) spans into their union,
+/// sorted by entry point.
+fn merge_spans(mut spans:Vec<(f64,f64)>) -> Vec<(f64,f64)> {
+    if spans.is_empty() {
+        return spans;
+    }
+    spans.sort_by(|a,b| a.0.partial_cmp(&b.0).unwrap());
+    let mut merged=vec![spans[0]];
+    for &(t0,t1) in &spans[1..] {
+        let last=merged.last_mut().unwrap();
+        if t0<=last.1 {
+            if t1>last.1 {
+                last.1=t1;
+            }
+        } else {
+            merged.push((t0,t1));
+        }
+    }
+    merged
+}
+
+/// Intersect (in the set sense) two span lists, keeping only the
+/// sub-intervals covered by both.
+fn intersect_spans(a:&[(f64,f64)], b:&[(f64,f64)]) -> Vec<(f64,f64)> {
+    let mut result=Vec::new();
+    for &(a0,a1) in a {
+        for &(b0,b1) in b {
+            let lo=a0.max(b0);
+            let hi=a1.min(b1);
+            if lo<hi {
+                result.push((lo,hi));
+            }
+        }
+    }
+    result
+}
+
+/// Subtract span list `b` from span list `a`, splitting an `a` span when a
+/// `b` span lies entirely inside it.
+fn subtract_spans(a:&[(f64,f64)], b:&[(f64,f64)]) -> Vec<(f64,f64)> {
+    let mut result=a.to_vec();
+    for &(b0,b1) in b {
+        let mut next=Vec::new();
+        for (a0,a1) in result {
+            if b1<=a0 || b0>=a1 {
+                // No overlap; this span survives untouched.
+                next.push((a0,a1));
+            } else {
+                if b0>a0 {
+                    next.push((a0,b0));
+                }
+                if b1<a1 {
+                    next.push((b1,a1));
+                }
+            }
+        }
+        result=next;
+    }
+    result
+}
+
+/// CSG intersection: a point is inside only if it's inside every child.
+pub struct Intersection {
+    pub itemList:Vec<Box<dyn Render>>,
+    _transforms:TransformList,
+    _m_rb:HMatrix,
+    _m_br:HMatrix,
+}
+
+impl Intersection {
+    pub fn make() -> Intersection {
+        Intersection{_m_rb:HMatrix::identity(), _m_br: HMatrix::identity(), _transforms: vec![],itemList:vec![] }
+    }
+}
+
+impl Render for Intersection {
+    fn prepare_render(&mut self) {
+        let M_rb=self.get_transforms().get_matrix();
+        let M_br=M_rb.inv();
+        self.set_M_rb(M_rb);
+        self.set_M_br(M_br);
+        for this_render in &mut self.itemList {
+            this_render.prepare_render();
+        }
+    }
 
+    fn intersect_spans_local(&self, rv_b: &Ray) -> Vec<(f64,f64)> {
+        if self.itemList.is_empty() {
+            return vec![];
+        }
+        let rv_i=self.itemList[0].M_br().mul_ray(rv_b);
+        let mut spans=self.itemList[0].intersect_spans_local(&rv_i);
+        for this_render in &self.itemList[1..] {
+            let rv_i=this_render.M_br().mul_ray(rv_b);
+            spans=intersect_spans(&spans, &this_render.intersect_spans_local(&rv_i));
         }
-        result
+        spans
+    }
+
+    /// An `Intersection` has no surface of its own; resolve the normal by
+    /// finding which child contributed span endpoint `t`.
+    fn normal_local(&self, rv_b: &Ray, t:f64) -> Direction {
+        normal_from_children(self.itemList.iter(), rv_b, t)
     }
 
     fn M_rb(&self) -> &HMatrix {
@@ -185,5 +504,77 @@ impl Render for Union {
     fn get_transforms(&mut self) -> &mut TransformList {
         &mut self._transforms
     }
+}
+
+/// CSG difference: everything inside `a` that is not also inside `b`.
+pub struct Difference {
+    pub a:Box<dyn Render>,
+    pub b:Box<dyn Render>,
+    _transforms:TransformList,
+    _m_rb:HMatrix,
+    _m_br:HMatrix,
+}
+
+impl Difference {
+    pub fn make(a:Box<dyn Render>, b:Box<dyn Render>) -> Difference {
+        Difference{a, b, _m_rb:HMatrix::identity(), _m_br: HMatrix::identity(), _transforms: vec![] }
+    }
+}
+
+impl Render for Difference {
+    fn prepare_render(&mut self) {
+        let M_rb=self.get_transforms().get_matrix();
+        let M_br=M_rb.inv();
+        self.set_M_rb(M_rb);
+        self.set_M_br(M_br);
+        self.a.prepare_render();
+        self.b.prepare_render();
+    }
+
+    fn intersect_spans_local(&self, rv_b: &Ray) -> Vec<(f64,f64)> {
+        let rv_a=self.a.M_br().mul_ray(rv_b);
+        let rv_b_local=self.b.M_br().mul_ray(rv_b);
+        subtract_spans(&self.a.intersect_spans_local(&rv_a), &self.b.intersect_spans_local(&rv_b_local))
+    }
+
+    /// A `Difference` has no surface of its own; resolve the normal by
+    /// finding which of `a`/`b` contributed span endpoint `t`.
+    ///
+    /// A surface contributed by `b` is carved out of `a`, so the result's
+    /// outward normal there points into `b` rather than out of it — the
+    /// opposite of `b`'s own outward normal. `a`'s own surface is unchanged
+    /// and keeps its normal as-is, so only the `b` case gets negated.
+    fn normal_local(&self, rv_b: &Ray, t:f64) -> Direction {
+        const EPS:f64=1e-9;
+        let rv_a=self.a.M_br().mul_ray(rv_b);
+        let a_is_endpoint=self.a.intersect_spans_local(&rv_a).iter()
+            .any(|&(t0,t1)| (t0-t).abs()<EPS || (t1-t).abs()<EPS);
+        if a_is_endpoint {
+            let n_a=self.a.normal_local(&rv_a,t);
+            return self.a.M_rb().mul_normal(&n_a);
+        }
+        let rv_b_local=self.b.M_br().mul_ray(rv_b);
+        let n_b=self.b.M_rb().mul_normal(&self.b.normal_local(&rv_b_local,t));
+        Direction{x:-n_b.x,y:-n_b.y,z:-n_b.z}
+    }
+
+    fn M_rb(&self) -> &HMatrix {
+        &self._m_rb
+    }
+
+    fn M_br(&self) -> &HMatrix {
+        &self._m_br
+    }
+
+    fn set_M_rb(&mut self,M_rb:HMatrix) {
+        self._m_rb=M_rb;
+    }
 
-}
\ No newline at end of file
+    fn set_M_br(&mut self,M_br:HMatrix) {
+        self._m_br=M_br;
+    }
+
+    fn get_transforms(&mut self) -> &mut TransformList {
+        &mut self._transforms
+    }
+}
@@ -1,4 +1,4 @@
-use crate::vector::{Direction, HMatrix, Matrix3x3};
+use crate::vector::{Direction, HMatrix, Matrix3x3, Quaternion};
 
 pub trait Transform {
     fn get_matrix(&self)->HMatrix;
@@ -23,10 +23,16 @@ impl Transform for Translate {
     }
 }
 
-struct UniformScale {
+pub(crate) struct UniformScale {
     S:f64,
 }
 
+impl UniformScale {
+    pub(crate) fn make(s:f64) ->UniformScale {
+        UniformScale{S:s}
+    }
+}
+
 impl Transform for UniformScale {
     fn get_matrix(&self) -> HMatrix {
         HMatrix{M:Matrix3x3{e:[[self.S,0.0,0.0],
@@ -99,6 +105,81 @@ impl Transform for RotateZ {
     }
 }
 
+/// Rotation by `angle` radians about an arbitrary `axis`, avoiding the
+/// gimbal-stacking of composing `RotateX/Y/Z`.
+pub struct RotateAxis {
+    axis:Direction,
+    angle:f64,
+}
+
+impl RotateAxis {
+    pub(crate) fn make(axis:Direction, angle:f64) ->RotateAxis {
+        RotateAxis{axis,angle}
+    }
+}
+
+impl Transform for RotateAxis {
+    fn get_matrix(&self) -> HMatrix {
+        let q=Quaternion::from_axis_angle(&self.axis,self.angle);
+        HMatrix{M:q.to_matrix(),
+            T:Direction{x:0.0,y:0.0,z:0.0}
+        }
+    }
+}
+
+/// Rotation given directly as a `Quaternion`.
+pub struct RotateQuat {
+    q:Quaternion,
+}
+
+impl RotateQuat {
+    pub(crate) fn make(q:Quaternion) ->RotateQuat {
+        RotateQuat{q}
+    }
+}
+
+impl Transform for RotateQuat {
+    fn get_matrix(&self) -> HMatrix {
+        HMatrix{M:self.q.to_matrix(),
+            T:Direction{x:0.0,y:0.0,z:0.0}
+        }
+    }
+}
+
+/// A uniform scale, a rotation, and a translation bundled into a single
+/// `HMatrix`, computed in the canonical scale-then-rotate-then-translate
+/// order: `p' = R*(S*p) + T`.
+///
+/// Building the same instance out of `TransformList` pieces takes three
+/// boxed transforms folded together; `Similarity` gets there in one node,
+/// mirroring nalgebra's `Similarity`/isometry concept.
+pub struct Similarity {
+    scale:f64,
+    rotation:Quaternion,
+    translation:Direction,
+}
+
+impl Similarity {
+    pub(crate) fn make(scale:f64, rotation:Quaternion, translation:Direction) ->Similarity {
+        Similarity{scale,rotation,translation}
+    }
+    pub(crate) fn from_axis_angle(scale:f64, axis:&Direction, angle:f64, translation:Direction) ->Similarity {
+        Similarity{scale,rotation:Quaternion::from_axis_angle(axis,angle),translation}
+    }
+}
+
+impl Transform for Similarity {
+    fn get_matrix(&self) -> HMatrix {
+        let r=self.rotation.to_matrix();
+        let s=self.scale;
+        HMatrix{M:Matrix3x3{e:[[r.e[0][0]*s,r.e[0][1]*s,r.e[0][2]*s],
+                               [r.e[1][0]*s,r.e[1][1]*s,r.e[1][2]*s],
+                               [r.e[2][0]*s,r.e[2][1]*s,r.e[2][2]*s]]},
+                T:Direction{x:self.translation.x,y:self.translation.y,z:self.translation.z}
+        }
+    }
+}
+
 pub type TransformList=Vec<Box<dyn Transform>>;
 
 /// Get a single transformation matrix from a vector of transforms.
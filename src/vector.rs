@@ -20,6 +20,12 @@ pub struct Direction {
     pub z:f64,
 }
 
+/// A surface normal. Normals are direction vectors, but unlike a plain
+/// `Direction` they must be carried through `HMatrix::mul_normal` (the
+/// inverse-transpose of the upper 3x3 block) rather than `HMatrix::mul_dir`,
+/// since that's what keeps them perpendicular to a scaled/sheared surface.
+pub type Normal = Direction;
+
 impl Vector for Position {
     fn _x(&self)->f64 {self.x}
     fn _y(&self)->f64 {self.y}
@@ -128,6 +134,17 @@ impl Matrix3x3 {
                      [B*detinv,E*detinv,H*detinv],
                      [C*detinv,F*detinv,I*detinv]]}
     }
+    fn transpose(&self) -> Matrix3x3 {
+        let a=&self.e;
+        Matrix3x3{e:[[a[0][0],a[1][0],a[2][0]],
+                     [a[0][1],a[1][1],a[2][1]],
+                     [a[0][2],a[1][2],a[2][2]]]}
+    }
+    /// Inverse-transpose `M^-T`, used to transform normal vectors so they
+    /// stay perpendicular to a scaled/sheared surface.
+    fn inv_transpose(&self) -> Matrix3x3 {
+        self.inv().transpose()
+    }
 }
 
 pub struct HMatrix {
@@ -137,15 +154,21 @@ pub struct HMatrix {
 
 impl HMatrix {
     pub(crate) fn mul(&self, rhs: &HMatrix) -> HMatrix {
-        HMatrix {M:self.M.mul(&self.M),
+        HMatrix {M:self.M.mul(&rhs.M),
                  T:self.M.mul_dir(&rhs.T).add_dir(&self.T)}
     }
     fn mul_dir(&self, rhs: &Direction) -> Direction {
         self.M.mul_dir(rhs)
     }
-    fn mul_pos(&self, rhs: &Position) -> Position {
+    pub(crate) fn mul_pos(&self, rhs: &Position) -> Position {
         self.M.mul_pos(rhs).add_dir(&self.T)
     }
+    /// Transform a normal by the inverse-transpose of the upper 3x3 block.
+    /// Normals never participate in translation, same as `mul_dir`, but
+    /// they need the inverse-transpose rather than `M` itself.
+    pub(crate) fn mul_normal(&self, rhs: &Normal) -> Normal {
+        self.M.inv_transpose().mul_dir(rhs)
+    }
     fn inv(&self) -> HMatrix {
         let Am1=self.M.inv();
         HMatrix{T:Am1.mul_neg_dir(&self.T),M:Am1}
@@ -169,6 +192,53 @@ pub(crate) trait Vector {
     }
 }
 
+/// Unit quaternion representing a rotation, avoiding the gimbal-stacking
+/// that comes from composing `RotateX/Y/Z`.
+///
+/// Stored in scalar-first form, `q=w+x*i+y*j+z*k`.
+pub struct Quaternion {
+    pub w:f64,
+    pub x:f64,
+    pub y:f64,
+    pub z:f64,
+}
+
+impl Quaternion {
+    /// Build the unit quaternion for a right-handed rotation of `angle`
+    /// radians about `axis`. `axis` is normalized internally, so it need
+    /// not already be a unit vector.
+    pub fn from_axis_angle(axis:&Direction, angle:f64)->Quaternion {
+        let norm=(axis.x*axis.x+axis.y*axis.y+axis.z*axis.z).sqrt();
+        let half=angle/2.0;
+        let s=half.sin()/norm;
+        Quaternion{w:half.cos(),x:axis.x*s,y:axis.y*s,z:axis.z*s}
+    }
+    /// Hamilton product `self*rhs`, letting callers accumulate rotations
+    /// without going through matrices.
+    pub fn mul(&self,rhs:&Quaternion)->Quaternion {
+        Quaternion{
+            w:self.w*rhs.w-self.x*rhs.x-self.y*rhs.y-self.z*rhs.z,
+            x:self.w*rhs.x+self.x*rhs.w+self.y*rhs.z-self.z*rhs.y,
+            y:self.w*rhs.y-self.x*rhs.z+self.y*rhs.w+self.z*rhs.x,
+            z:self.w*rhs.z+self.x*rhs.y-self.y*rhs.x+self.z*rhs.w,
+        }
+    }
+    /// Scale to unit length, so that non-unit input still converts to a
+    /// proper rotation matrix.
+    fn normalize(&self)->Quaternion {
+        let n=(self.w*self.w+self.x*self.x+self.y*self.y+self.z*self.z).sqrt();
+        Quaternion{w:self.w/n,x:self.x/n,y:self.y/n,z:self.z/n}
+    }
+    /// Convert to the equivalent rotation matrix, normalizing first.
+    pub(crate) fn to_matrix(&self)->Matrix3x3 {
+        let q=self.normalize();
+        let (w,x,y,z)=(q.w,q.x,q.y,q.z);
+        Matrix3x3{e:[[1.0-2.0*(y*y+z*z), 2.0*(x*y-w*z),     2.0*(x*z+w*y)    ],
+                     [2.0*(x*y+w*z),     1.0-2.0*(x*x+z*z), 2.0*(y*z-w*x)    ],
+                     [2.0*(x*z-w*y),     2.0*(y*z+w*x),     1.0-2.0*(x*x+y*y)]]}
+    }
+}
+
 pub struct Ray {
     pub(crate) r0:Position,
     pub(crate) v:Direction,